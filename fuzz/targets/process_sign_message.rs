@@ -21,12 +21,8 @@ fn main() {
                     .collect();
 
                 let msg = SignRequest { tx };
-                let resp = cosignerd::processing::process_sign_message(
-                    &builder.config,
-                    msg,
-                    &builder.bitcoin_privkey,
-                )
-                .expect("We should never crash while processing a message");
+                let resp = cosignerd::processing::process_sign_message(&builder.config, msg)
+                    .expect("We should never crash while processing a message");
 
                 if let Some(resp_tx) = resp.tx {
                     let psbt = resp_tx.inner_tx();
@@ -0,0 +1,76 @@
+//! Crash-safe atomic file writes.
+//!
+//! A direct `write_all` into the destination path leaves a window where a crash mid-write
+//! produces a truncated, unrecoverable file. `atomic_write` instead writes to a temporary
+//! file in the *same* directory as the destination, `fsync`s it, `rename`s it over the
+//! destination (atomic on the same filesystem), then `fsync`s the containing directory so
+//! the rename itself is durable across a power loss.
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+pub fn atomic_write(dir: &Path, name: &str, bytes: &[u8], mode: u32) -> io::Result<()> {
+    let tmp_path = dir.join(format!(".{}.tmp", name));
+    let final_path = dir.join(name);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(mode);
+    let mut tmp_fd = options.open(&tmp_path)?;
+    tmp_fd.write_all(bytes)?;
+    tmp_fd.sync_all()?;
+    drop(tmp_fd);
+
+    fs::rename(&tmp_path, &final_path)?;
+
+    // fsync the directory too, so the rename itself can't be lost to a crash.
+    let dir_fd = fs::File::open(dir)?;
+    dir_fd.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cosignerd-atomic-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn atomic_write_creates_file_with_mode() {
+        let dir = scratch_dir("create");
+        atomic_write(&dir, "secret", b"hello", 0o400).unwrap();
+
+        let path = dir.join("secret");
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o400);
+        // The temporary file must not be left behind.
+        assert!(!dir.join(".secret.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_write_overwrites_existing_file() {
+        let dir = scratch_dir("overwrite");
+        atomic_write(&dir, "secret", b"first", 0o400).unwrap();
+        atomic_write(&dir, "secret", b"second", 0o400).unwrap();
+
+        assert_eq!(fs::read(dir.join("secret")).unwrap(), b"second");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,17 @@
 use crate::config::{datadir_path, Config, ConfigError, ManagerConfig};
+use crate::database::{self, DatabaseError};
+use crate::permissions;
+use crate::seal::{self, SealError};
+use crate::signer::{RemoteSigner, Signer, SoftwareSigner};
 
 use revault_net::{noise::SecretKey as NoisePrivKey, sodiumoxide};
+use zeroize::Zeroize;
 
 use std::{
     fs,
-    io::{self, Read, Write},
+    io::{self},
     net::SocketAddr,
-    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    os::unix::fs::DirBuilderExt,
     path::PathBuf,
 };
 
@@ -16,6 +21,14 @@ pub enum CosignerDError {
     NoiseKeyError(io::Error),
     ConfigError(ConfigError),
     DatadirCreation(io::Error),
+    /// We couldn't even `stat` a path component while auditing permissions.
+    PermissionAudit(PathBuf, io::Error),
+    /// A path component (or the secret file itself) has untrustworthy permission bits.
+    InsecurePermissions(PathBuf, u32),
+    /// Sealing or opening an at-rest secret failed.
+    Seal(SealError),
+    /// Creating or querying the signed-outpoint database failed.
+    Database(DatabaseError),
 }
 
 impl std::fmt::Display for CosignerDError {
@@ -24,24 +37,53 @@ impl std::fmt::Display for CosignerDError {
             Self::NoiseKeyError(e) => write!(f, "Noise key initialization error: '{}'", e),
             Self::ConfigError(e) => write!(f, "Configuration error: '{}'", e),
             Self::DatadirCreation(e) => write!(f, "Creating data directory: '{}'", e),
+            Self::PermissionAudit(p, e) => {
+                write!(f, "Could not check permissions of '{:?}': '{}'", p, e)
+            }
+            Self::InsecurePermissions(p, mode) => write!(
+                f,
+                "Insecure permissions (mode {:o}) on '{:?}': refusing to trust it with a secret. \
+                 Set COSIGNERD_FS_DISABLE_PERMISSION_CHECKS=true to downgrade this to a warning.",
+                mode & 0o777,
+                p
+            ),
+            Self::Seal(e) => write!(f, "{}", e),
+            Self::Database(e) => write!(f, "{}", e),
         }
     }
 }
 
 /// Our global state
-#[derive(Debug)]
 pub struct CosignerD {
     pub managers: Vec<ManagerConfig>,
 
-    pub noise_privkey: NoisePrivKey,
+    // Boxed so its address is stable from the moment it's `mlock()`ed in
+    // `read_or_create_noise_key` through every subsequent move into this struct: `mlock()`ing
+    // a stack-local value would only protect whichever address it happened to occupy at the
+    // time, not wherever it ends up living. `mlock()`ed for as long as `self` is alive and
+    // zeroized on drop, see `Drop` below.
+    pub noise_privkey: Box<NoisePrivKey>,
+
+    // The Bitcoin signing key isn't necessarily held in-process: it may live behind a
+    // `RemoteSigner` talking to an external device or agent. Either way, `process_sign_message`
+    // only ever goes through this trait object.
+    pub signer: Box<dyn Signer>,
+    // The public key matching whichever key `signer` holds: we always know this regardless
+    // of where the private key actually lives, since it's part of the output descriptor.
+    pub bitcoin_pubkey: revault_tx::bitcoin::PublicKey,
 
     pub listen: SocketAddr,
     // We store all our data in one place, that's here.
     pub data_dir: PathBuf,
 }
 
-// The communication keys are (for now) hot, so we just create it ourselves on first run.
-fn read_or_create_noise_key(secret_file: &PathBuf) -> Result<NoisePrivKey, CosignerDError> {
+// The communication keys are (for now) hot, so we just create it ourselves on first run. The
+// key is sealed at rest behind a passphrase-derived key (see the `seal` module); a file that
+// predates this is assumed to be a legacy plaintext key and is transparently upgraded.
+fn read_or_create_noise_key(
+    secret_file: &PathBuf,
+    passphrase_file: &PathBuf,
+) -> Result<Box<NoisePrivKey>, CosignerDError> {
     let mut noise_secret = NoisePrivKey([0; 32]);
 
     if !secret_file.as_path().exists() {
@@ -51,26 +93,59 @@ fn read_or_create_noise_key(secret_file: &PathBuf) -> Result<NoisePrivKey, Cosig
         );
         noise_secret = sodiumoxide::crypto::box_::gen_keypair().1;
 
-        // We create it in read-only but open it in write only.
-        let mut options = fs::OpenOptions::new();
-        options = options.write(true).create_new(true).mode(0o400).clone();
-
-        let mut fd = options
-            .open(secret_file)
-            .map_err(CosignerDError::NoiseKeyError)?;
-        fd.write_all(&noise_secret.as_ref())
-            .map_err(CosignerDError::NoiseKeyError)?;
+        seal::seal(secret_file, passphrase_file, noise_secret.as_ref())
+            .map_err(CosignerDError::Seal)?;
     } else {
-        let mut noise_secret_fd =
-            fs::File::open(secret_file).map_err(CosignerDError::NoiseKeyError)?;
-        noise_secret_fd
-            .read_exact(&mut noise_secret.0)
-            .map_err(CosignerDError::NoiseKeyError)?;
-    }
+        let raw = fs::read(secret_file).map_err(CosignerDError::NoiseKeyError)?;
 
-    // TODO: have a decent memory management and mlock() the key
+        if seal::is_sealed(&raw) {
+            let opened = seal::open(secret_file, passphrase_file).map_err(CosignerDError::Seal)?;
+            if opened.len() != 32 {
+                return Err(CosignerDError::NoiseKeyError(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sealed Noise key at '{:?}' decrypts to {} bytes, expected 32",
+                        secret_file,
+                        opened.len()
+                    ),
+                )));
+            }
+            noise_secret.0.copy_from_slice(&opened);
+        } else {
+            if raw.len() != 32 {
+                return Err(CosignerDError::NoiseKeyError(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Noise key file at '{:?}' is {} bytes, expected either a sealed file \
+                         or a legacy 32-byte plaintext key",
+                        secret_file,
+                        raw.len()
+                    ),
+                )));
+            }
+            log::warn!(
+                "Noise key at '{:?}' is in the legacy plaintext format, sealing it at rest",
+                secret_file
+            );
+            noise_secret.0.copy_from_slice(&raw);
+            seal::seal(secret_file, passphrase_file, &raw).map_err(CosignerDError::Seal)?;
+        }
+    }
 
     assert!(noise_secret.0 != [0; 32]);
+
+    // Box it *before* `mlock()`ing: a `Box<T>`'s heap allocation doesn't move for the rest of
+    // its life, unlike `noise_secret` itself, which would otherwise be free to move (and so
+    // change address) on its way into `CosignerD`. The key lives at this address for as long
+    // as `CosignerD` is alive, so it's this memory -- not just the transient stack buffer used
+    // to get here -- that must stay out of swap. It's unlocked and zeroized in `CosignerD`'s
+    // `Drop` impl.
+    let noise_secret = Box::new(noise_secret);
+    let res = unsafe { libc::mlock(noise_secret.0.as_ptr() as *const _, noise_secret.0.len()) };
+    if res != 0 {
+        log::warn!("Could not mlock() the Noise private key, it may end up swapped to disk");
+    }
+
     Ok(noise_secret)
 }
 
@@ -92,16 +167,40 @@ impl CosignerD {
         }
         data_dir = fs::canonicalize(data_dir).map_err(CosignerDError::DatadirCreation)?;
 
+        // Audit the datadir itself unconditionally: a freshly-pointed-at, pre-existing
+        // directory never goes through `create_datadir` (which only runs when the datadir
+        // doesn't exist yet), so this is the only place a group/other-writable datadir
+        // inherited from a permissive umask would ever get caught.
+        permissions::audit_directory(&data_dir, &data_dir)?;
+
         let mut noise_key_path = data_dir.clone();
         noise_key_path.push("noise_secret");
-        let noise_privkey = read_or_create_noise_key(&noise_key_path)?;
+        if noise_key_path.as_path().exists() {
+            permissions::audit_path(&noise_key_path, &data_dir)?;
+        }
+        let noise_privkey = read_or_create_noise_key(&noise_key_path, &config.passphrase_file)?;
+
+        let signer: Box<dyn Signer> = match config.signer_socket_path {
+            Some(socket_path) => Box::new(RemoteSigner::new(socket_path)),
+            None => Box::new(SoftwareSigner::new(config.bitcoin_privkey)),
+        };
 
-        Ok(CosignerD {
+        let cosignerd = CosignerD {
             managers,
             noise_privkey,
+            signer,
+            bitcoin_pubkey: config.bitcoin_pubkey,
             listen,
             data_dir,
-        })
+        };
+
+        // The database is created (if needed) and audited here rather than in the
+        // `database` module's own startup path, since that's the only place we can be sure
+        // to run it exactly once, right after the datadir itself has been vetted above.
+        database::setup_db(&cosignerd.db_file()).map_err(CosignerDError::Database)?;
+        cosignerd.audit_db_permissions()?;
+
+        Ok(cosignerd)
     }
 
     fn file_from_datadir(&self, file_name: &str) -> PathBuf {
@@ -124,4 +223,45 @@ impl CosignerD {
     pub fn db_file(&self) -> PathBuf {
         self.file_from_datadir("cosignerd.sqlite3")
     }
+
+    /// Audit the permissions of the signed-outpoint database, if it already exists.
+    ///
+    /// This is separate from the checks done in `from_config` because the database file is
+    /// only created once the `database` module has run its startup migrations.
+    pub fn audit_db_permissions(&self) -> Result<(), CosignerDError> {
+        let db_file = self.db_file();
+        if db_file.as_path().exists() {
+            permissions::audit_path(&db_file, &self.data_dir)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Hand-written rather than derived: `Signer` is deliberately not `Debug` (so a `SoftwareSigner`
+// can never have its private key printed via a blanket derive), which makes `Box<dyn Signer>`
+// itself not `Debug`. The Noise key is left out for the same reason.
+impl std::fmt::Debug for CosignerD {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CosignerD")
+            .field("managers", &self.managers)
+            .field("noise_privkey", &"<redacted>")
+            .field("signer", &"<redacted>")
+            .field("bitcoin_pubkey", &self.bitcoin_pubkey)
+            .field("listen", &self.listen)
+            .field("data_dir", &self.data_dir)
+            .finish()
+    }
+}
+
+impl Drop for CosignerD {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munlock(
+                self.noise_privkey.0.as_ptr() as *const _,
+                self.noise_privkey.0.len(),
+            );
+        }
+        self.noise_privkey.0.zeroize();
+    }
 }
@@ -0,0 +1,88 @@
+//! Storage for which outpoints we already cosigned a spend for.
+//!
+//! We must never answer a `SignRequest` before the fact that we signed its outpoints is
+//! durably on disk: a crash between producing the signature and recording it would let the
+//! same outpoint be signed for (and so spent) twice across a restart. We get this guarantee
+//! from SQLite itself by forcing `PRAGMA synchronous = FULL`, which fsyncs on every commit.
+
+use revault_tx::bitcoin::OutPoint;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use std::{fs, io, os::unix::fs::PermissionsExt, path::Path};
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Rusqlite(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Rusqlite(e) => write!(f, "Database error: '{}'", e),
+            Self::Io(e) => write!(f, "I/O error setting up database file: '{}'", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(e: rusqlite::Error) -> Self {
+        Self::Rusqlite(e)
+    }
+}
+
+fn connect(db_path: &Path) -> Result<Connection, DatabaseError> {
+    let conn = Connection::open(db_path)?;
+    conn.pragma_update(None, "synchronous", "FULL")?;
+    Ok(conn)
+}
+
+/// Create the `signed_outpoints` table if it doesn't exist yet.
+pub fn setup_db(db_path: &Path) -> Result<(), DatabaseError> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS signed_outpoints (
+            outpoint TEXT PRIMARY KEY NOT NULL
+        )",
+        [],
+    )?;
+    drop(conn);
+
+    // SQLite creates the file according to the umask, which is typically world-readable.
+    // This holds signed-outpoint history, so lock it down before anything (including our
+    // own permission audit) gets a chance to look at it.
+    fs::set_permissions(db_path, fs::Permissions::from_mode(0o600)).map_err(DatabaseError::Io)?;
+
+    Ok(())
+}
+
+/// Did we already sign for this outpoint?
+pub fn db_signed_outpoint(
+    db_path: &Path,
+    outpoint: &OutPoint,
+) -> Result<Option<()>, DatabaseError> {
+    let conn = connect(db_path)?;
+
+    let found: Option<String> = conn
+        .query_row(
+            "SELECT outpoint FROM signed_outpoints WHERE outpoint = ?1",
+            params![outpoint.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    Ok(found.map(|_| ()))
+}
+
+/// Durably record that we signed for this outpoint. This commits with `synchronous = FULL`,
+/// so by the time it returns the record has survived an fsync and it's safe to answer the
+/// `SignRequest` that triggered it.
+pub fn db_insert_signed_outpoint(db_path: &Path, outpoint: &OutPoint) -> Result<(), DatabaseError> {
+    let conn = connect(db_path)?;
+    conn.execute(
+        "INSERT OR IGNORE INTO signed_outpoints (outpoint) VALUES (?1)",
+        params![outpoint.to_string()],
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,195 @@
+//! Auditing of filesystem permissions for the datadir and the secrets stored within it.
+//!
+//! We hold a hot Noise private key and a database recording which outpoints we already
+//! signed for: both must stay unreadable to anyone but us. Before ever touching them we
+//! walk the path from the target up to a trust-root directory and make sure no component
+//! was tampered with (wrong owner) or left group/other writable, and that the target
+//! itself (when it's a secret file) isn't group/other readable.
+//!
+//! Containers are frequently run as root with a permissive umask, so an operator can
+//! explicitly opt out of these checks (turning failures into warnings) by setting the
+//! `COSIGNERD_FS_DISABLE_PERMISSION_CHECKS` environment variable to `"true"`.
+
+use crate::cosignerd::CosignerDError;
+
+use std::{
+    env, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+const DISABLE_CHECKS_ENV_VAR: &str = "COSIGNERD_FS_DISABLE_PERMISSION_CHECKS";
+
+fn checks_disabled() -> bool {
+    env::var(DISABLE_CHECKS_ENV_VAR)
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+/// Is any group or other write bit set in this mode?
+fn is_group_or_other_writable(mode: u32) -> bool {
+    mode & 0o022 != 0
+}
+
+/// Is any group or other read bit set in this mode?
+fn is_group_or_other_readable(mode: u32) -> bool {
+    mode & 0o044 != 0
+}
+
+/// Check a single path component. A secret file must not be group/other readable; any
+/// other (directory) component must not be group/other writable. Every component must be
+/// owned by us or by root.
+fn check_component(path: &Path, is_secret_file: bool) -> Result<(), CosignerDError> {
+    let metadata =
+        fs::metadata(path).map_err(|e| CosignerDError::PermissionAudit(path.to_path_buf(), e))?;
+
+    let our_uid = unsafe { libc::getuid() };
+    let mode = metadata.mode();
+    if metadata.uid() != our_uid && metadata.uid() != 0 {
+        return Err(CosignerDError::InsecurePermissions(path.to_path_buf(), mode));
+    }
+
+    let is_insecure = if is_secret_file {
+        is_group_or_other_readable(mode)
+    } else {
+        is_group_or_other_writable(mode)
+    };
+    if is_insecure {
+        return Err(CosignerDError::InsecurePermissions(path.to_path_buf(), mode));
+    }
+
+    Ok(())
+}
+
+fn audit_directory_inner(dir: &Path, trust_root: &Path) -> Result<(), CosignerDError> {
+    let mut current = dir.to_path_buf();
+    loop {
+        check_component(&current, false)?;
+        if current == trust_root {
+            break;
+        }
+        match current.parent() {
+            Some(parent) if parent != current => current = parent.to_path_buf(),
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn audit_path_inner(target: &Path, trust_root: &Path) -> Result<(), CosignerDError> {
+    check_component(target, true)?;
+
+    let parent = target.parent().unwrap_or_else(|| Path::new("/"));
+    audit_directory_inner(parent, trust_root)
+}
+
+fn with_escape_hatch(res: Result<(), CosignerDError>) -> Result<(), CosignerDError> {
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) if checks_disabled() => {
+            log::warn!(
+                "Ignoring filesystem permission issue because {} is set: {}",
+                DISABLE_CHECKS_ENV_VAR,
+                e
+            );
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Starting at `target` (a secret file) and walking up to, and including, `trust_root`,
+/// verify that every path component is owned by us (or root) and isn't writable by
+/// group/other, and that `target` itself isn't readable by group/other.
+///
+/// On failure this returns `CosignerDError::InsecurePermissions` (or `PermissionAudit` if
+/// we couldn't even `stat` a component) naming the offending path, unless
+/// `COSIGNERD_FS_DISABLE_PERMISSION_CHECKS` is set to `"true"`, in which case the failure is
+/// logged as a warning and ignored.
+pub fn audit_path(target: &Path, trust_root: &Path) -> Result<(), CosignerDError> {
+    with_escape_hatch(audit_path_inner(target, trust_root))
+}
+
+/// Same as `audit_path`, but for a directory that doesn't itself hold a secret (e.g. the
+/// datadir before any secret has been read from or written to it). Every component from
+/// `dir` up to, and including, `trust_root` must be owned by us (or root) and not be
+/// writable by group/other.
+pub fn audit_directory(dir: &Path, trust_root: &Path) -> Result<(), CosignerDError> {
+    with_escape_hatch(audit_directory_inner(dir, trust_root))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn group_or_other_write_bits() {
+        assert!(!is_group_or_other_writable(0o700));
+        assert!(!is_group_or_other_writable(0o750));
+        assert!(is_group_or_other_writable(0o720));
+        assert!(is_group_or_other_writable(0o702));
+        assert!(is_group_or_other_writable(0o777));
+    }
+
+    #[test]
+    fn group_or_other_read_bits() {
+        assert!(!is_group_or_other_readable(0o400));
+        assert!(!is_group_or_other_readable(0o430));
+        assert!(is_group_or_other_readable(0o440));
+        assert!(is_group_or_other_readable(0o404));
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cosignerd-permissions-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        dir
+    }
+
+    #[test]
+    fn audit_path_accepts_trustworthy_secret() {
+        let dir = scratch_dir("ok");
+        let secret = dir.join("noise_secret");
+        fs::write(&secret, b"secret").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o400)).unwrap();
+
+        assert!(audit_path(&secret, &dir).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn audit_path_rejects_world_readable_secret() {
+        let dir = scratch_dir("bad-secret");
+        let secret = dir.join("noise_secret");
+        fs::write(&secret, b"secret").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o444)).unwrap();
+
+        assert!(matches!(
+            audit_path(&secret, &dir),
+            Err(CosignerDError::InsecurePermissions(..))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn audit_directory_rejects_world_writable_component() {
+        let dir = scratch_dir("bad-dir");
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+        assert!(matches!(
+            audit_directory(&dir, &dir),
+            Err(CosignerDError::InsecurePermissions(..))
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! Processing of `SignRequest`s coming in from managers.
+
+use crate::cosignerd::CosignerD;
+use crate::database::{self, DatabaseError};
+use crate::signer::SignerError;
+
+use revault_net::message::cosigner::{SignRequest, SignResponse};
+use revault_tx::{
+    bitcoin::{secp256k1, util::sighash::SighashCache, EcdsaSighashType},
+    transactions::RevaultTransaction,
+};
+
+#[derive(Debug)]
+pub enum ProcessingError {
+    Signer(SignerError),
+    Database(DatabaseError),
+}
+
+impl std::fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Signer(e) => write!(f, "{}", e),
+            Self::Database(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<SignerError> for ProcessingError {
+    fn from(e: SignerError) -> Self {
+        Self::Signer(e)
+    }
+}
+
+impl From<DatabaseError> for ProcessingError {
+    fn from(e: DatabaseError) -> Self {
+        Self::Database(e)
+    }
+}
+
+/// Sign every input of this spend transaction, going through `cosignerd.signer` rather than
+/// ever touching a raw private key ourselves.
+///
+/// An outpoint we already signed for is never signed again: if any input spends one, we
+/// answer with no transaction. Every newly-signed outpoint is durably recorded before we
+/// answer (see the `database` module).
+pub fn process_sign_message(
+    cosignerd: &CosignerD,
+    msg: SignRequest,
+) -> Result<SignResponse, ProcessingError> {
+    let SignRequest { mut tx } = msg;
+    let db_path = cosignerd.db_file();
+
+    let unsigned_tx = tx.inner_tx().global.unsigned_tx.clone();
+    let mut sighash_cache = SighashCache::new(&unsigned_tx);
+
+    for txin in unsigned_tx.input.iter() {
+        if database::db_signed_outpoint(&db_path, &txin.previous_output)?.is_some() {
+            return Ok(SignResponse { tx: None });
+        }
+    }
+
+    for index in 0..unsigned_tx.input.len() {
+        let outpoint = unsigned_tx.input[index].previous_output;
+
+        let psbt_input = &tx.inner_tx().inputs[index];
+        let witness_utxo = psbt_input
+            .witness_utxo
+            .clone()
+            .expect("Our inputs always carry a witness UTXO");
+        let script_code = psbt_input
+            .witness_script
+            .clone()
+            .expect("Our inputs always carry a witness script");
+
+        let sighash = sighash_cache
+            .segwit_signature_hash(
+                index,
+                &script_code,
+                witness_utxo.value,
+                EcdsaSighashType::All,
+            )
+            .expect("Input index is always valid and script code is always set above");
+        let message = secp256k1::Message::from_slice(&sighash[..])
+            .expect("SigHash is always exactly 32 bytes");
+
+        let signature = cosignerd.signer.sign_spend_input(&message)?;
+        let mut der_sig = signature.serialize_der().to_vec();
+        der_sig.push(EcdsaSighashType::All as u8);
+
+        tx.inner_tx_mut().inputs[index]
+            .partial_sigs
+            .insert(cosignerd.bitcoin_pubkey, der_sig);
+
+        database::db_insert_signed_outpoint(&db_path, &outpoint)?;
+    }
+
+    Ok(SignResponse { tx: Some(tx) })
+}
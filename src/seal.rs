@@ -0,0 +1,231 @@
+//! Crypto-at-rest for our hot secrets. `CosignerD` seals the Noise private key with it; the
+//! Bitcoin private key is not sealed by this module (it comes in-process from the config).
+//!
+//! Secrets are no longer stored as raw bytes on disk. We derive a 32-byte wrapping key from
+//! an operator-supplied passphrase with Argon2id, and seal the secret with
+//! XChaCha20-Poly1305. The on-disk layout is a single versioned file:
+//!
+//!     version (1 byte) || salt (16 bytes) || nonce (24 bytes) || ciphertext || tag (16 bytes)
+//!
+//! A file that's exactly the size of a raw secret (as opposed to this versioned layout) is
+//! assumed to be a legacy plaintext key and is transparently sealed in place on next read.
+
+use crate::atomic::atomic_write;
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::{rngs::OsRng, RngCore};
+use zeroize::Zeroizing;
+
+use std::{
+    fmt, fs,
+    io::{self},
+    ops::Deref,
+    path::Path,
+};
+
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const HEADER_LEN: usize = 1 + SALT_LEN + NONCE_LEN;
+
+#[derive(Debug)]
+pub enum SealError {
+    Io(io::Error),
+    Passphrase(io::Error),
+    KeyDerivation,
+    Encryption,
+    /// The ciphertext's authentication tag didn't match: wrong passphrase, or the file was
+    /// tampered with.
+    AuthenticationFailed,
+    /// The sealed file is shorter than our fixed header, so it can't possibly be one of ours.
+    Truncated,
+}
+
+impl fmt::Display for SealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error sealing/opening secret: '{}'", e),
+            Self::Passphrase(e) => write!(f, "Could not read passphrase file: '{}'", e),
+            Self::KeyDerivation => write!(f, "Argon2id key derivation failed"),
+            Self::Encryption => write!(f, "AEAD encryption failed"),
+            Self::AuthenticationFailed => write!(
+                f,
+                "Could not decrypt secret: wrong passphrase, or the file was tampered with"
+            ),
+            Self::Truncated => write!(f, "Sealed secret file is truncated or corrupt"),
+        }
+    }
+}
+
+/// A decrypted secret. It is `mlock()`ed for as long as it's alive, and zeroized on drop.
+pub struct SealedSecret(Zeroizing<Vec<u8>>);
+
+impl SealedSecret {
+    fn new(bytes: Vec<u8>) -> Self {
+        let bytes = Zeroizing::new(bytes);
+        let res = unsafe { libc::mlock(bytes.as_ptr() as *const _, bytes.len()) };
+        if res != 0 {
+            log::warn!("Could not mlock() the decrypted secret, it may end up swapped to disk");
+        }
+        Self(bytes)
+    }
+}
+
+impl Deref for SealedSecret {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Drop for SealedSecret {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munlock(self.0.as_ptr() as *const _, self.0.len());
+        }
+    }
+}
+
+fn read_passphrase(path: &Path) -> Result<Zeroizing<Vec<u8>>, SealError> {
+    let mut raw = fs::read(path).map_err(SealError::Passphrase)?;
+    if raw.last() == Some(&b'\n') {
+        raw.pop();
+    }
+    Ok(Zeroizing::new(raw))
+}
+
+fn derive_key(passphrase: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LEN]>, SealError> {
+    let mut key = Zeroizing::new([0u8; KEY_LEN]);
+    Argon2::default()
+        .hash_password_into(passphrase, salt, &mut *key)
+        .map_err(|_| SealError::KeyDerivation)?;
+    Ok(key)
+}
+
+/// Is this on-disk content one of our sealed files, as opposed to a legacy plaintext secret?
+pub fn is_sealed(raw: &[u8]) -> bool {
+    raw.len() > HEADER_LEN && raw[0] == FORMAT_VERSION
+}
+
+/// Derive a wrapping key from the passphrase at `passphrase_file` and seal `secret` into
+/// `path`, creating it (mode 0o400) or overwriting it if it already exists.
+pub fn seal(path: &Path, passphrase_file: &Path, secret: &[u8]) -> Result<(), SealError> {
+    let passphrase = read_passphrase(passphrase_file)?;
+
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    let key = derive_key(&passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new(key.as_slice().into());
+    let nonce = XNonce::from_slice(&nonce_bytes);
+    let ciphertext = cipher
+        .encrypt(nonce, secret)
+        .map_err(|_| SealError::Encryption)?;
+
+    let mut out = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+    out.push(FORMAT_VERSION);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce_bytes);
+    out.extend_from_slice(&ciphertext);
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .expect("sealed secret path always has a file name")
+        .to_str()
+        .expect("sealed secret path is valid unicode");
+    atomic_write(dir, name, &out, 0o400).map_err(SealError::Io)?;
+
+    Ok(())
+}
+
+/// Open and decrypt a file written by `seal`, deriving the wrapping key from the passphrase
+/// at `passphrase_file`.
+pub fn open(path: &Path, passphrase_file: &Path) -> Result<SealedSecret, SealError> {
+    let raw = fs::read(path).map_err(SealError::Io)?;
+    if raw.len() <= HEADER_LEN {
+        return Err(SealError::Truncated);
+    }
+
+    let salt = &raw[1..1 + SALT_LEN];
+    let nonce_bytes = &raw[1 + SALT_LEN..HEADER_LEN];
+    let ciphertext = &raw[HEADER_LEN..];
+
+    let passphrase = read_passphrase(passphrase_file)?;
+    let key = derive_key(&passphrase, salt)?;
+    let cipher = XChaCha20Poly1305::new(key.as_slice().into());
+    let nonce = XNonce::from_slice(nonce_bytes);
+
+    let plaintext = cipher
+        .decrypt(nonce, ciphertext)
+        .map_err(|_| SealError::AuthenticationFailed)?;
+
+    Ok(SealedSecret::new(plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "cosignerd-seal-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn is_sealed_rejects_legacy_plaintext() {
+        // A raw 32-byte Noise key, as it would have been stored before this module existed.
+        assert!(!is_sealed(&[0u8; 32]));
+    }
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let dir = scratch_dir("roundtrip");
+        let secret_path = dir.join("noise_secret");
+        let passphrase_path = dir.join("passphrase");
+        fs::write(&passphrase_path, b"correct horse battery staple\n").unwrap();
+
+        let secret = [0x42u8; 32];
+        seal(&secret_path, &passphrase_path, &secret).unwrap();
+
+        let raw = fs::read(&secret_path).unwrap();
+        assert!(is_sealed(&raw));
+
+        let opened = open(&secret_path, &passphrase_path).unwrap();
+        assert_eq!(&opened[..], &secret[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_rejects_wrong_passphrase() {
+        let dir = scratch_dir("wrong-passphrase");
+        let secret_path = dir.join("noise_secret");
+        let passphrase_path = dir.join("passphrase");
+        let other_passphrase_path = dir.join("other_passphrase");
+        fs::write(&passphrase_path, b"right passphrase").unwrap();
+        fs::write(&other_passphrase_path, b"wrong passphrase").unwrap();
+
+        seal(&secret_path, &passphrase_path, &[0x11u8; 32]).unwrap();
+
+        assert!(matches!(
+            open(&secret_path, &other_passphrase_path),
+            Err(SealError::AuthenticationFailed)
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! Abstraction over where the Bitcoin signing key actually lives.
+//!
+//! `CosignerD` never touches raw key bytes itself: it holds a `Box<dyn Signer>` chosen at
+//! startup, and hands sighashes to it. The default is `SoftwareSigner`, which keeps the key
+//! in-process exactly as before. `RemoteSigner` instead forwards the sighash to an external
+//! signing device or agent over a Unix socket, so operators can keep the cosigner's key in
+//! an HSM while `cosignerd` itself only ever sees sighashes and signatures.
+
+use revault_tx::bitcoin::{
+    secp256k1::{self, Secp256k1},
+    PrivateKey as BitcoinPrivKey,
+};
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+/// An error while signing, or while setting up a signer.
+#[derive(Debug)]
+pub enum SignerError {
+    /// Couldn't connect to, write to, or read from the remote signer's socket.
+    Io(io::Error),
+    /// The remote signer's reply didn't parse as a valid signature.
+    InvalidSignature,
+}
+
+impl fmt::Display for SignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error talking to the signer: '{}'", e),
+            Self::InvalidSignature => write!(f, "Signer returned an invalid signature"),
+        }
+    }
+}
+
+/// Something that can produce an ECDSA signature for a sighash, without necessarily
+/// exposing the private key that produces it.
+///
+/// Deliberately *not* `: fmt::Debug` -- a blanket derive pulled in by whoever holds a
+/// `Box<dyn Signer>` must never be able to print the live signing key.
+pub trait Signer: Send + Sync {
+    fn sign_spend_input(
+        &self,
+        sighash: &secp256k1::Message,
+    ) -> Result<secp256k1::ecdsa::Signature, SignerError>;
+}
+
+/// The historical behaviour: the Bitcoin private key lives in our process' memory and we
+/// sign with it directly. The key is boxed (so its address is stable across moves of
+/// `SoftwareSigner` itself) and `mlock()`ed for as long as it's alive; `Debug` is hand-written
+/// to never print it.
+pub struct SoftwareSigner {
+    bitcoin_privkey: Box<BitcoinPrivKey>,
+}
+
+impl SoftwareSigner {
+    pub fn new(bitcoin_privkey: BitcoinPrivKey) -> Self {
+        let bitcoin_privkey = Box::new(bitcoin_privkey);
+        let res = unsafe {
+            libc::mlock(
+                &*bitcoin_privkey as *const BitcoinPrivKey as *const _,
+                std::mem::size_of::<BitcoinPrivKey>(),
+            )
+        };
+        if res != 0 {
+            log::warn!("Could not mlock() the Bitcoin private key, it may end up swapped to disk");
+        }
+
+        Self { bitcoin_privkey }
+    }
+}
+
+impl fmt::Debug for SoftwareSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SoftwareSigner")
+            .field("bitcoin_privkey", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Drop for SoftwareSigner {
+    fn drop(&mut self) {
+        let ptr = &mut *self.bitcoin_privkey as *mut BitcoinPrivKey as *mut u8;
+        let len = std::mem::size_of::<BitcoinPrivKey>();
+        unsafe {
+            for i in 0..len {
+                std::ptr::write_volatile(ptr.add(i), 0);
+            }
+            libc::munlock(ptr as *const _, len);
+        }
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn sign_spend_input(
+        &self,
+        sighash: &secp256k1::Message,
+    ) -> Result<secp256k1::ecdsa::Signature, SignerError> {
+        let secp = Secp256k1::signing_only();
+        Ok(secp.sign_ecdsa(sighash, &self.bitcoin_privkey.inner))
+    }
+}
+
+/// Delegates signing to an external process (a signing device driver, or an agent guarding
+/// an HSM) reachable over a Unix socket. The wire format is trivial: we write the 32-byte
+/// sighash, then read back a DER-encoded ECDSA signature terminated by the peer closing its
+/// side of the socket.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    socket_path: PathBuf,
+}
+
+impl RemoteSigner {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Self { socket_path }
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn sign_spend_input(
+        &self,
+        sighash: &secp256k1::Message,
+    ) -> Result<secp256k1::ecdsa::Signature, SignerError> {
+        let mut stream = UnixStream::connect(&self.socket_path).map_err(SignerError::Io)?;
+        stream
+            .write_all(&sighash[..])
+            .map_err(SignerError::Io)?;
+        stream.flush().map_err(SignerError::Io)?;
+
+        let mut sig_der = Vec::new();
+        stream
+            .read_to_end(&mut sig_der)
+            .map_err(SignerError::Io)?;
+
+        secp256k1::ecdsa::Signature::from_der(&sig_der).map_err(|_| SignerError::InvalidSignature)
+    }
+}